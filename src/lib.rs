@@ -25,8 +25,9 @@
 extern crate libc;
 
 use std::io;
-use std::process::{Command, Child, ExitStatus};
+use std::process::{ChildStderr, ChildStdin, ChildStdout, Command, Child, ExitStatus};
 use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
 
 #[cfg(not(windows))]
 #[path="unix.rs"]
@@ -61,6 +62,32 @@ impl SharedChild {
         })
     }
 
+    /// Create a new `SharedChild` from an already-spawned `std::process::Child`.
+    ///
+    /// This is useful for adopting a child that was spawned with custom setup
+    /// this crate doesn't expose (for example pre-exec hooks), or one handed
+    /// to you by another library. Because the `Child` could have already
+    /// exited (or even have already been reaped) before it gets here, `new`
+    /// calls `try_wait` on it right away to establish the initial state and to
+    /// grab the handle/PID while they're still guaranteed valid. Note that
+    /// this initial check can reap a lingering zombie on Unix, as a side
+    /// effect of determining whether the child has already exited.
+    pub fn new(mut child: Child) -> io::Result<SharedChild> {
+        let id = child.id();
+        let handle = sys::get_handle(&child);
+        let initial_state = match child.try_wait()? {
+            Some(exit_status) => Exited(exit_status),
+            None => NotWaiting,
+        };
+        Ok(SharedChild {
+            id,
+            handle,
+            child: Mutex::new(child),
+            state_lock: Mutex::new(initial_state),
+            state_condvar: Condvar::new(),
+        })
+    }
+
     pub fn id(&self) -> u32 {
         self.id
     }
@@ -112,8 +139,11 @@ impl SharedChild {
         // and signal the state condvar.
         let mut state = self.state_lock.lock().unwrap();
         let final_result = noreap_result.and_then(|_| {
-            // Reap the child. Errors only short-circuit this closure.
-            if let Some(exit_status) = sys::try_wait(&self.handle)? {
+            // Reap the child through its own try_wait, rather than a raw
+            // syscall, so that std::process::Child caches the exit status
+            // internally. That's what lets into_inner() hand back a Child
+            // whose own wait() works afterwards.
+            if let Some(exit_status) = self.child.lock().unwrap().try_wait()? {
                 Ok(exit_status)
             } else {
                 // This should never happen, unless waitid lied to us.
@@ -129,6 +159,92 @@ impl SharedChild {
         final_result
     }
 
+    /// Wait for the child to exit, blocking the current thread for at most
+    /// `timeout`, and return its exit status. Returns `Ok(None)` if `timeout`
+    /// elapses before the child exits, in which case the child is still
+    /// running and can be waited on again.
+    pub fn wait_timeout(&self, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state_lock.lock().unwrap();
+        loop {
+            match *state {
+                NotWaiting => {
+                    // Same as in wait(), break out and become the waiter.
+                    break;
+                }
+                Waiting => {
+                    // Another thread is already waiting on the child. Block on
+                    // the condvar until it signals us, or until our own
+                    // deadline runs out, recomputing the remaining time across
+                    // spurious wakeups.
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    let (new_state, wait_result) = self
+                        .state_condvar
+                        .wait_timeout(state, deadline - now)
+                        .unwrap();
+                    state = new_state;
+                    if wait_result.timed_out() {
+                        return match *state {
+                            Exited(exit_status) => Ok(Some(exit_status)),
+                            _ => Ok(None),
+                        };
+                    }
+                }
+                Exited(exit_status) => return Ok(Some(exit_status)),
+            }
+        }
+
+        // We're the thread responsible for waiting on the child. Same
+        // choreography as wait(): publish Waiting, drop the lock, and make
+        // sure we leave the Waiting state before this function returns.
+        *state = Waiting;
+        drop(state);
+
+        let now = Instant::now();
+        let remaining = if deadline > now {
+            deadline - now
+        } else {
+            Duration::new(0, 0)
+        };
+        let timeout_result = sys::wait_timeout(&self.handle, remaining);
+
+        let mut state = self.state_lock.lock().unwrap();
+        let result = match timeout_result {
+            Ok(true) => {
+                // The child exited. Reap it now, under the state lock, the
+                // same way wait() does: through the Child's own try_wait, so
+                // that into_inner() can later hand back a Child whose own
+                // wait() still works.
+                let final_result = self.child.lock().unwrap().try_wait().and_then(|opt| {
+                    opt.ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::Other, "blocking wait after child exit")
+                    })
+                });
+                *state = if let Ok(exit_status) = final_result {
+                    Exited(exit_status)
+                } else {
+                    NotWaiting
+                };
+                final_result.map(Some)
+            }
+            Ok(false) => {
+                // We timed out without the child exiting. Go back to
+                // NotWaiting so the next caller (or this one) tries again.
+                *state = NotWaiting;
+                Ok(None)
+            }
+            Err(e) => {
+                *state = NotWaiting;
+                Err(e)
+            }
+        };
+        self.state_condvar.notify_all();
+        result
+    }
+
     /// Return the child's exit status if it has already exited. If the child is
     /// still running, return `Ok(None)`.
     pub fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
@@ -148,7 +264,7 @@ impl SharedChild {
         // If it has, put ourselves in the Exited state. (There can't be any
         // other waiters to signal, because the state was NotWaiting when we
         // started, and we're still holding the status lock.)
-        if let Some(exit_status) = sys::try_wait(&self.handle)? {
+        if let Some(exit_status) = self.child.lock().unwrap().try_wait()? {
             *status = Exited(exit_status);
             Ok(Some(exit_status))
         } else {
@@ -166,6 +282,66 @@ impl SharedChild {
         // The child is still running. Kill it.
         self.child.lock().unwrap().kill()
     }
+
+    /// Send a kill signal to the child, unless it's already exited, and then
+    /// wait for it, guaranteeing that it's reaped and leaves no zombie on
+    /// Unix. This avoids the foot-gun in `kill`'s docs, where callers have to
+    /// remember to `wait` afterwards themselves. If another thread is
+    /// already the designated waiter, this call doesn't race it with a
+    /// second reap attempt; it just blocks on the condvar for that thread's
+    /// result, same as a plain `wait` would.
+    pub fn kill_and_wait(&self) -> io::Result<ExitStatus> {
+        {
+            let status = self.state_lock.lock().unwrap();
+            if let Exited(exit_status) = *status {
+                return Ok(exit_status);
+            }
+            self.child.lock().unwrap().kill()?;
+        }
+        self.wait()
+    }
+
+    /// Take the child's stdin handle, if it was spawned with
+    /// `Stdio::piped()`, leaving `None` in its place. This lets one thread
+    /// write to the child's stdin while another thread calls `wait` or
+    /// `kill` on the `SharedChild`.
+    pub fn take_stdin(&self) -> Option<ChildStdin> {
+        self.child.lock().unwrap().stdin.take()
+    }
+
+    /// Take the child's stdout handle, if it was spawned with
+    /// `Stdio::piped()`, leaving `None` in its place. This lets one thread
+    /// read the child's stdout while another thread calls `wait` or `kill` on
+    /// the `SharedChild`.
+    pub fn take_stdout(&self) -> Option<ChildStdout> {
+        self.child.lock().unwrap().stdout.take()
+    }
+
+    /// Take the child's stderr handle, if it was spawned with
+    /// `Stdio::piped()`, leaving `None` in its place. This lets one thread
+    /// read the child's stderr while another thread calls `wait` or `kill` on
+    /// the `SharedChild`.
+    pub fn take_stderr(&self) -> Option<ChildStderr> {
+        self.child.lock().unwrap().stderr.take()
+    }
+
+    /// Consume the `SharedChild` and return the wrapped `std::process::Child`.
+    ///
+    /// Once unwrapped, this crate's PID-reuse protection no longer applies:
+    /// the `waitid(WNOWAIT)`-based coordination described in the module docs
+    /// is gone, and the raw `Child::wait`/`Child::kill` race that this crate
+    /// exists to avoid is back in effect. Only do this once you're done
+    /// coordinating `wait`/`kill` across threads, for example to hand the
+    /// child off to some other API that expects a plain `Child`.
+    ///
+    /// If a `wait`, `wait_timeout` or `try_wait` on the `SharedChild` (or
+    /// `new`) already saw the child exit, it's already been reaped through the
+    /// `Child`'s own `try_wait`, which caches the exit status internally, so
+    /// a subsequent call to `Child::wait` on the returned value will return
+    /// that cached status rather than erroring.
+    pub fn into_inner(self) -> Child {
+        self.child.into_inner().unwrap()
+    }
 }
 
 enum ChildState {
@@ -191,6 +367,33 @@ mod tests {
         assert_eq!(status.code().unwrap(), 0);
     }
 
+    #[test]
+    fn test_new() {
+        let child = Command::new("true").spawn().unwrap();
+        let shared_child = SharedChild::new(child).unwrap();
+        assert!(shared_child.id() > 0);
+        let status = shared_child.wait().unwrap();
+        assert_eq!(status.code().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_new_adopts_already_exited_child() {
+        // Give the child time to exit on its own before we ever hand it to
+        // SharedChild::new, so that new() has to establish the Exited state
+        // (and reap the zombie) itself, instead of the common case where the
+        // child is still running.
+        let child = Command::new("true").spawn().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        let shared_child = SharedChild::new(child).unwrap();
+        // wait()/try_wait() should both return the cached status right away,
+        // with no blocking and no second reap of a zombie that's already
+        // gone.
+        let maybe_status = shared_child.try_wait().unwrap();
+        assert_eq!(maybe_status.unwrap().code().unwrap(), 0);
+        let status = shared_child.wait().unwrap();
+        assert_eq!(status.code().unwrap(), 0);
+    }
+
     #[test]
     fn test_try_wait() {
         // This is a hack to check that try_wait will clean up a child that has
@@ -209,6 +412,58 @@ mod tests {
         assert!(maybe_status.is_some());
     }
 
+    #[test]
+    fn test_wait_timeout() {
+        let child = SharedChild::spawn(Command::new("sleep").arg("0.1")).unwrap();
+        // The child isn't done yet, so a short timeout should elapse first.
+        let maybe_status = child
+            .wait_timeout(std::time::Duration::from_millis(10))
+            .unwrap();
+        assert_eq!(maybe_status, None);
+        // A longer timeout should be enough to observe the real exit.
+        let status = child
+            .wait_timeout(std::time::Duration::from_millis(500))
+            .unwrap()
+            .unwrap();
+        assert_eq!(status.code().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_wait_timeout_many_waiters() {
+        // One thread becomes the designated waiter, blocking on the child
+        // directly via wait(). The rest call wait_timeout() with a short
+        // timeout, and must come back promptly by timing out on the condvar,
+        // not by blocking until the (long-lived) child actually exits.
+        let child = Arc::new(SharedChild::spawn(Command::new("sleep").arg("1000")).unwrap());
+        let waiter_child = child.clone();
+        let waiter = std::thread::spawn(move || waiter_child.wait());
+
+        // Give the waiter thread a moment to become the designated waiter.
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        let mut condvar_waiters = Vec::new();
+        for _ in 0..10 {
+            let clone = child.clone();
+            condvar_waiters.push(std::thread::spawn(move || {
+                let start = std::time::Instant::now();
+                let maybe_status = clone
+                    .wait_timeout(std::time::Duration::from_millis(50))
+                    .unwrap();
+                (maybe_status, start.elapsed())
+            }));
+        }
+        for thread in condvar_waiters {
+            let (maybe_status, elapsed) = thread.join().unwrap();
+            assert_eq!(maybe_status, None);
+            // Generous upper bound: this should return close to the 50ms
+            // timeout, not after the child's full 1000 second lifetime.
+            assert!(elapsed < std::time::Duration::from_secs(5));
+        }
+
+        child.kill().unwrap();
+        waiter.join().unwrap().unwrap();
+    }
+
     #[test]
     fn test_kill() {
         let child = SharedChild::spawn(Command::new("sleep").arg("1000")).unwrap();
@@ -219,6 +474,62 @@ mod tests {
         child.kill().unwrap();
     }
 
+    #[test]
+    fn test_into_inner() {
+        let shared_child = SharedChild::spawn(&mut Command::new("true")).unwrap();
+        shared_child.wait().unwrap();
+        let mut child = shared_child.into_inner();
+        // The child was already reaped, so this should return the cached
+        // status rather than erroring.
+        let status = child.wait().unwrap();
+        assert_eq!(status.code().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_kill_and_wait() {
+        let child = SharedChild::spawn(Command::new("sleep").arg("1000")).unwrap();
+        let status = child.kill_and_wait().unwrap();
+        assert!(!status.success());
+        // Calling it again after exit should just return the cached status.
+        let status_again = child.kill_and_wait().unwrap();
+        assert_eq!(status, status_again);
+    }
+
+    #[test]
+    fn test_kill_and_wait_with_existing_waiter() {
+        // One thread becomes the designated waiter via plain wait(). Another
+        // thread calls kill_and_wait(), which must kill the child and then
+        // hand off to the condvar for the result, rather than racing the
+        // designated waiter with a second waitid/reap.
+        let child = Arc::new(SharedChild::spawn(Command::new("sleep").arg("1000")).unwrap());
+        let waiter_child = child.clone();
+        let waiter = std::thread::spawn(move || waiter_child.wait());
+
+        // Give the waiter thread a moment to become the designated waiter.
+        std::thread::sleep(std::time::Duration::from_millis(50));
+
+        let status = child.kill_and_wait().unwrap();
+        assert!(!status.success());
+        assert_eq!(waiter.join().unwrap().unwrap(), status);
+    }
+
+    #[test]
+    fn test_take_stdout() {
+        use std::io::Read;
+        use std::process::Stdio;
+
+        let mut command = Command::new("echo");
+        command.arg("hi").stdout(Stdio::piped());
+        let child = SharedChild::spawn(&mut command).unwrap();
+        let mut stdout = child.take_stdout().unwrap();
+        // A second take should come back empty, since it's already gone.
+        assert!(child.take_stdout().is_none());
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).unwrap();
+        assert_eq!(output.trim(), "hi");
+        child.wait().unwrap();
+    }
+
     #[test]
     fn test_many_waiters() {
         let child = Arc::new(SharedChild::spawn(Command::new("sleep").arg("1000")).unwrap());
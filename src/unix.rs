@@ -0,0 +1,154 @@
+use libc::{c_int, c_long, pid_t};
+use std::io;
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::process::Child;
+use std::time::{Duration, Instant};
+
+pub struct Handle {
+    pid: pid_t,
+    // A pidfd for this process, opened with pidfd_open() if we're on a
+    // kernel that supports it (Linux 5.3+). wait_timeout() polls this fd to
+    // wait with a timeout. On older kernels, and on every other Unix this
+    // file is compiled for (macOS, the BSDs, ...), pidfd_open() doesn't
+    // exist at all, so this is always None there and we fall back to
+    // polling waitid(WNOHANG | WNOWAIT) instead.
+    pidfd: Option<RawFd>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        if let Some(fd) = self.pidfd {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+    }
+}
+
+// pidfd_open(2) is Linux-only (not part of POSIX, and not present even on
+// other libc::linux_like-adjacent Unixes like the BSDs or macOS).
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn pidfd_open(pid: pid_t) -> Option<RawFd> {
+    let ret = unsafe { libc::syscall(libc::SYS_pidfd_open as c_long, pid, 0 as c_int) };
+    if ret < 0 {
+        None
+    } else {
+        Some(ret as RawFd)
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn pidfd_open(_pid: pid_t) -> Option<RawFd> {
+    None
+}
+
+pub fn get_handle(child: &Child) -> Handle {
+    let pid = child.id() as pid_t;
+    Handle {
+        pid,
+        pidfd: pidfd_open(pid),
+    }
+}
+
+// Wait for the child to exit, without reaping it. This uses waitid() with
+// the WNOWAIT flag, which leaves the child in a waitable state afterwards,
+// so that a concurrent kill() can't race against the PID being reused.
+pub fn wait_without_reaping(handle: &Handle) -> io::Result<()> {
+    loop {
+        let mut siginfo = unsafe { mem::zeroed() };
+        let ret = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                handle.pid as libc::id_t,
+                &mut siginfo,
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if ret == 0 {
+            return Ok(());
+        }
+        let error = io::Error::last_os_error();
+        if error.kind() != io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+// Wait for the child to exit, without reaping it, blocking for at most
+// `timeout`. Returns Ok(true) if the child exited, Ok(false) on timeout.
+pub fn wait_timeout(handle: &Handle, timeout: Duration) -> io::Result<bool> {
+    match handle.pidfd {
+        Some(fd) => poll_pidfd(fd, timeout),
+        None => poll_without_pidfd(handle, timeout),
+    }
+}
+
+fn poll_pidfd(fd: RawFd, timeout: Duration) -> io::Result<bool> {
+    // A ready pidfd (POLLIN) means the process has exited. Polling doesn't
+    // reap it; the caller still needs to call try_wait() afterwards.
+    let deadline = Instant::now() + timeout;
+    let mut pollfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        let now = Instant::now();
+        let remaining = if deadline > now {
+            deadline - now
+        } else {
+            Duration::new(0, 0)
+        };
+        let millis = remaining.as_millis().min(c_int::MAX as u128) as c_int;
+        let ret = unsafe { libc::poll(&mut pollfd, 1, millis) };
+        if ret < 0 {
+            let error = io::Error::last_os_error();
+            // A signal delivered while we're parked in poll() (e.g. a
+            // SIGCHLD from some other child exiting) must not reset our
+            // timeout budget; recompute the remaining time above and retry.
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+            continue;
+        }
+        return Ok(ret > 0);
+    }
+}
+
+// Kernels without pidfd_open() (pre-5.3) have no way to block on a specific
+// child with a timeout, so fall back to a short polling loop with capped
+// exponential backoff. We use waitid(WNOHANG | WNOWAIT) rather than
+// try_wait() so that, like the pidfd path, this only detects exit without
+// reaping; the caller reaps separately under the state lock.
+fn poll_without_pidfd(handle: &Handle, timeout: Duration) -> io::Result<bool> {
+    let deadline = Instant::now() + timeout;
+    let mut delay = Duration::from_millis(1);
+    let max_delay = Duration::from_millis(50);
+    loop {
+        let mut siginfo = unsafe { mem::zeroed() };
+        let ret = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                handle.pid as libc::id_t,
+                &mut siginfo,
+                libc::WEXITED | libc::WNOWAIT | libc::WNOHANG,
+            )
+        };
+        if ret < 0 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        } else if unsafe { siginfo.si_pid() } != 0 {
+            return Ok(true);
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(delay.min(deadline - now));
+        delay = (delay * 2).min(max_delay);
+    }
+}
@@ -0,0 +1,45 @@
+use std::io;
+use std::os::windows::io::{AsRawHandle, RawHandle};
+use std::process::Child;
+use std::time::Duration;
+
+use winapi::shared::minwindef::DWORD;
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::winbase::{WAIT_OBJECT_0, WAIT_TIMEOUT};
+
+// On Windows, a Child's handle stays valid (and its PID can't be reused)
+// until the handle is closed, so there's no equivalent of Unix's
+// wait-without-reaping dance needed to avoid PID races.
+pub struct Handle {
+    raw: RawHandle,
+}
+
+unsafe impl Send for Handle {}
+unsafe impl Sync for Handle {}
+
+pub fn get_handle(child: &Child) -> Handle {
+    Handle {
+        raw: child.as_raw_handle(),
+    }
+}
+
+pub fn wait_without_reaping(handle: &Handle) -> io::Result<()> {
+    let ret = unsafe { WaitForSingleObject(handle.raw, winapi::um::winbase::INFINITE) };
+    if ret == WAIT_OBJECT_0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+// WaitForSingleObject already supports a timeout natively, so there's no
+// need for anything like Unix's pidfd/polling fallback.
+pub fn wait_timeout(handle: &Handle, timeout: Duration) -> io::Result<bool> {
+    let millis = timeout.as_millis().min(DWORD::MAX as u128 - 1) as DWORD;
+    let ret = unsafe { WaitForSingleObject(handle.raw, millis) };
+    match ret {
+        WAIT_OBJECT_0 => Ok(true),
+        WAIT_TIMEOUT => Ok(false),
+        _ => Err(io::Error::last_os_error()),
+    }
+}